@@ -1,12 +1,23 @@
+mod network;
+
+use std::{net::SocketAddr, thread, time::Duration};
+
 use anyhow::Result;
-use log::error;
+use log::{error, info, warn};
 use log_error::LogError;
-use midir::{Ignore, MidiInput, MidiInputConnection};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiInputPort, MidiOutput, MidiOutputConnection};
 use serde::Deserialize;
 use thiserror::Error;
-use tokio::sync::mpsc::{self, Receiver};
+use tokio::{
+    spawn,
+    sync::mpsc::{self, Receiver, Sender},
+};
 
-#[derive(Debug, PartialEq, Eq, Hash, Deserialize)]
+const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Input {
     Pad1,
@@ -68,23 +79,189 @@ impl TryFrom<u8> for Input {
     }
 }
 
+impl Input {
+    /// The note number used to address this input's LED, for pads only.
+    /// Knobs have no LED and are not addressable.
+    fn pad_note(self) -> Option<u8> {
+        match self {
+            Input::Pad1 => Some(0),
+            Input::Pad2 => Some(1),
+            Input::Pad3 => Some(2),
+            Input::Pad4 => Some(3),
+            Input::Pad5 => Some(4),
+            Input::Pad6 => Some(5),
+            Input::Pad7 => Some(6),
+            Input::Pad8 => Some(7),
+            Input::Knob1
+            | Input::Knob2
+            | Input::Knob3
+            | Input::Knob4
+            | Input::Knob5
+            | Input::Knob6
+            | Input::Knob7
+            | Input::Knob8 => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Disconnected,
+}
+
 pub enum Lpd8Message {
     ProgramChange(Input),
     ControlChange(Input, u8),
+    ConnectionState(ConnectionState),
+}
+
+/// LED state a pad can be put in to reflect the OBS element it is mapped to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadState {
+    Inactive,
+    Active,
+    Muted,
+}
+
+impl PadState {
+    fn velocity(self) -> u8 {
+        match self {
+            PadState::Inactive => 0,
+            PadState::Active => 127,
+            PadState::Muted => 64,
+        }
+    }
+}
+
+pub enum FeedbackMessage {
+    SetPadState(Input, PadState),
+    Reconnect,
+}
+
+pub enum InputBackend {
+    Local,
+    Network(SocketAddr),
 }
 
 pub struct Lpd8 {
     pub messages: Receiver<Lpd8Message>,
-    _connection: MidiInputConnection<()>,
+    _worker: thread::JoinHandle<()>,
 }
 
 impl Lpd8 {
-    pub fn connect() -> Result<Lpd8> {
-        let mut input = MidiInput::new("akai-lpd8-obs")?;
-        input.ignore(Ignore::None);
+    pub fn connect(backend: InputBackend) -> Lpd8 {
+        let (sender, receiver) = mpsc::channel(100);
+        let worker = match backend {
+            InputBackend::Local => thread::spawn(move || run_supervisor(sender)),
+            InputBackend::Network(addr) => {
+                thread::spawn(move || network::run_listener(sender, addr))
+            }
+        };
+
+        Lpd8 {
+            messages: receiver,
+            _worker: worker,
+        }
+    }
+}
+
+fn run_supervisor(sender: Sender<Lpd8Message>) {
+    let mut backoff = INITIAL_RECONNECT_BACKOFF;
+
+    loop {
+        match connect_once(&sender) {
+            Ok(connection) => {
+                backoff = INITIAL_RECONNECT_BACKOFF;
+                info!("Connected to LPD8");
+                if sender
+                    .blocking_send(Lpd8Message::ConnectionState(ConnectionState::Connected))
+                    .is_err()
+                {
+                    return;
+                }
+
+                wait_for_unplug();
+                drop(connection);
+                info!("LPD8 disconnected, will reconnect when it reappears");
+                if sender
+                    .blocking_send(Lpd8Message::ConnectionState(ConnectionState::Disconnected))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Err(err) => {
+                error!("Unable to connect to LPD8, retrying in {backoff:?}: {err}");
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+            }
+        }
+    }
+}
+
+fn connect_once(sender: &Sender<Lpd8Message>) -> Result<MidiInputConnection<()>> {
+    let mut input = MidiInput::new("akai-lpd8-obs")?;
+    input.ignore(Ignore::None);
+
+    let lpd8_port = find_port(&input)?.ok_or(LPD8Error::NotFound)?;
+    let sender = sender.clone();
+
+    input
+        .connect(
+            &lpd8_port,
+            "lpd8",
+            move |_, msg, _| {
+                if let Some(msg) = process_input(msg) {
+                    sender
+                        .blocking_send(msg)
+                        .log_error("Cannot send message to channel");
+                }
+            },
+            (),
+        )
+        .or(Err(LPD8Error::MidiError.into()))
+}
+
+fn find_port(input: &MidiInput) -> Result<Option<MidiInputPort>> {
+    for p in input.ports() {
+        if input.port_name(&p)?.contains("LPD8") {
+            return Ok(Some(p));
+        }
+    }
+    Ok(None)
+}
+
+fn wait_for_unplug() {
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        match MidiInput::new("akai-lpd8-obs-poll").and_then(|input| {
+            let present = find_port(&input)?.is_some();
+            Ok(present)
+        }) {
+            Ok(true) => continue,
+            Ok(false) => return,
+            Err(err) => {
+                error!("Unable to poll MIDI ports, assuming LPD8 was unplugged: {err}");
+                return;
+            }
+        }
+    }
+}
+
+/// Write side of the LPD8 connection, used to light up pads so the
+/// controller reflects the current OBS state.
+pub struct Lpd8Feedback {
+    connection: MidiOutputConnection,
+}
+
+impl Lpd8Feedback {
+    pub fn connect() -> Result<Lpd8Feedback> {
+        let output = MidiOutput::new("akai-lpd8-obs-feedback")?;
         let mut lpd8_port = None;
-        for p in input.ports() {
-            let name = input.port_name(&p)?;
+        for p in output.ports() {
+            let name = output.port_name(&p)?;
             if name.contains("LPD8") {
                 lpd8_port = Some(p);
                 break;
@@ -92,31 +269,60 @@ impl Lpd8 {
         }
 
         if let Some(lpd8_port) = lpd8_port {
-            let (sender, receiver) = mpsc::channel(100);
-
-            let connection = input
-                .connect(
-                    &lpd8_port,
-                    "lpd8",
-                    move |_, msg, _| {
-                        if let Some(msg) = process_input(msg) {
-                            sender
-                                .blocking_send(msg)
-                                .log_error("Cannot send message to channel");
-                        }
-                    },
-                    (),
-                )
+            let connection = output
+                .connect(&lpd8_port, "lpd8-feedback")
                 .or(Err(LPD8Error::MidiError))?;
 
-            return Ok(Lpd8 {
-                messages: receiver,
-                _connection: connection,
-            });
+            return Ok(Lpd8Feedback { connection });
         }
 
         Err(LPD8Error::NotFound.into())
     }
+
+    fn set_pad_state(&mut self, pad: Input, state: PadState) -> Result<()> {
+        let Some(note) = pad.pad_note() else {
+            return Ok(());
+        };
+
+        self.connection
+            .send(&[0x90, note, state.velocity()])
+            .or(Err(LPD8Error::MidiError))?;
+        Ok(())
+    }
+}
+
+/// Spawns the task owning the feedback connection and returns the sender
+/// used to push LED updates to it.
+pub fn spawn_feedback() -> Sender<FeedbackMessage> {
+    let (sender, mut receiver) = mpsc::channel(100);
+    let mut feedback = Lpd8Feedback::connect()
+        .inspect_err(|err| {
+            warn!("No LPD8 feedback output available, LED feedback disabled: {err}")
+        })
+        .ok();
+
+    spawn(async move {
+        while let Some(msg) = receiver.recv().await {
+            match msg {
+                FeedbackMessage::SetPadState(pad, state) => {
+                    if let Some(feedback) = feedback.as_mut()
+                        && let Err(err) = feedback.set_pad_state(pad, state)
+                    {
+                        error!("Unable to set pad {pad:?} to state {state:?}: {err}");
+                    }
+                }
+                FeedbackMessage::Reconnect => {
+                    feedback = Lpd8Feedback::connect()
+                        .inspect_err(|err| {
+                            warn!("Unable to reconnect LPD8 feedback output, LED feedback disabled: {err}")
+                        })
+                        .ok();
+                }
+            }
+        }
+    });
+
+    sender
 }
 
 fn process_input(msg: &[u8]) -> Option<Lpd8Message> {
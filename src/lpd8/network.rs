@@ -0,0 +1,61 @@
+use std::{
+    io::Read,
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+};
+
+use log::{error, info};
+use log_error::LogError;
+use tokio::sync::mpsc::Sender;
+
+use super::{Lpd8Message, process_input};
+
+pub fn run_listener(sender: Sender<Lpd8Message>, addr: SocketAddr) {
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Unable to bind network MIDI bridge on {addr}: {err}");
+            return;
+        }
+    };
+
+    info!("Listening for network MIDI input on {addr}");
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let sender = sender.clone();
+                thread::spawn(move || handle_connection(stream, sender));
+            }
+            Err(err) => error!("Error accepting network MIDI connection: {err}"),
+        }
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, sender: Sender<Lpd8Message>) {
+    let peer = stream
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|_| "<unknown>".to_string());
+    info!("Network MIDI bridge connected to {peer}");
+
+    let mut msg = [0u8; 3];
+    while stream.read_exact(&mut msg[..1]).is_ok() {
+        let len = message_len(msg[0]);
+        if len > 1 && stream.read_exact(&mut msg[1..len]).is_err() {
+            break;
+        }
+
+        if let Some(msg) = process_input(&msg[..len]) {
+            sender
+                .blocking_send(msg)
+                .log_error("Cannot send network MIDI message to channel");
+        }
+    }
+
+    info!("Network MIDI bridge lost connection to {peer}");
+}
+
+fn message_len(status: u8) -> usize {
+    if status & 0xC0 == 0xC0 { 2 } else { 3 }
+}
@@ -1,5 +1,6 @@
 mod lpd8;
 mod obs;
+mod script;
 
 use log::{LevelFilter, info};
 use log4rs::{
@@ -7,7 +8,9 @@ use log4rs::{
     append::console::ConsoleAppender,
     config::{Appender, Root},
 };
-use std::{collections::HashMap, fmt::Display, io::stdin};
+use std::{
+    collections::HashMap, fmt::Display, io::stdin, net::SocketAddr, str::FromStr, time::Duration,
+};
 
 use anyhow::Result;
 use clap::Parser;
@@ -16,8 +19,9 @@ use serde::Deserialize;
 use tokio::{fs::File, io::AsyncReadExt};
 
 use crate::{
-    lpd8::{Input, Lpd8},
+    lpd8::{Input, InputBackend, Lpd8, spawn_feedback},
     obs::Obs,
+    script::Script,
 };
 
 #[derive(Debug, Deserialize, Default)]
@@ -37,6 +41,7 @@ enum Action {
     EnableSceneItem { name: String },
     DisableSceneItem { name: String },
     ToggleSceneItem { name: String },
+    Script { path: String },
 }
 
 impl Display for Action {
@@ -56,6 +61,7 @@ impl Display for Action {
             Action::ToggleSceneItem { name } => {
                 f.write_fmt(format_args!("toggle scene item {name}"))
             }
+            Action::Script { path } => f.write_fmt(format_args!("run script {path}")),
         }
     }
 }
@@ -94,6 +100,65 @@ struct Args {
     pub port: u16,
     #[arg(short = 'P', long, env)]
     pub password: Option<String>,
+    #[arg(long, default_value_t = 30)]
+    pub cc_throttle_ms: u64,
+    #[arg(long, default_value = "local")]
+    pub input: InputBackendArg,
+}
+
+#[derive(Debug, Clone)]
+enum InputBackendArg {
+    Local,
+    Tcp(SocketAddr),
+}
+
+impl FromStr for InputBackendArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s == "local" {
+            Ok(InputBackendArg::Local)
+        } else if let Some(addr) = s.strip_prefix("tcp://") {
+            addr.parse()
+                .map(InputBackendArg::Tcp)
+                .map_err(|e| format!("invalid TCP address {addr}: {e}"))
+        } else {
+            Err(format!(
+                "unknown input backend {s}, expected \"local\" or \"tcp://<addr>\""
+            ))
+        }
+    }
+}
+
+impl From<InputBackendArg> for InputBackend {
+    fn from(arg: InputBackendArg) -> Self {
+        match arg {
+            InputBackendArg::Local => InputBackend::Local,
+            InputBackendArg::Tcp(addr) => InputBackend::Network(addr),
+        }
+    }
+}
+
+async fn load_scripts(mappings: &Mappings) -> Result<HashMap<String, Script>> {
+    let mut scripts = HashMap::new();
+    for action in mappings.program_changes.values() {
+        preload_script(action, &mut scripts).await?;
+    }
+    for group in &mappings.control_changes {
+        for conditional in group.values() {
+            preload_script(&conditional.action, &mut scripts).await?;
+        }
+    }
+    Ok(scripts)
+}
+
+async fn preload_script(action: &Action, scripts: &mut HashMap<String, Script>) -> Result<()> {
+    if let Action::Script { path } = action
+        && !scripts.contains_key(path)
+    {
+        scripts.insert(path.clone(), Script::load(path).await?);
+    }
+    Ok(())
 }
 
 #[tokio::main]
@@ -111,10 +176,15 @@ async fn main() -> Result<()> {
     f.read_to_string(&mut buffer).await?;
 
     let mappings: Mappings = toml::from_str(buffer.as_str())?;
+    let scripts = load_scripts(&mappings).await?;
 
-    let lpd8 = Lpd8::connect()?;
+    let lpd8 = Lpd8::connect(args.input.into());
+    let feedback = spawn_feedback();
     let obs = Obs::connect(args.host, args.port, args.password).await?;
-    let _handle = obs.start(mappings, lpd8.messages).await?;
+    let cc_throttle = Duration::from_millis(args.cc_throttle_ms);
+    let _handle = obs
+        .start(mappings, lpd8.messages, feedback, scripts, cc_throttle)
+        .await?;
 
     info!("OBS Controller is up and running, press [ENTER] to quit.");
     let mut input = String::new();
@@ -1,19 +1,25 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use anyhow::Result;
-use log::error;
+use log::{error, info};
 use obws::{
     Client,
     events::Event,
     requests::{inputs::Volume, scene_items::SetEnabled},
     responses::{inputs::InputId, scenes::SceneId},
 };
-use tokio::{pin, select, spawn, sync::mpsc::Receiver, task::JoinHandle};
+use tokio::{
+    pin, select, spawn,
+    sync::mpsc::{Receiver, Sender},
+    task::JoinHandle,
+    time::{Instant, sleep},
+};
 use tokio_stream::StreamExt;
 
 use crate::{
     Action, ConditionalAction, Mappings,
-    lpd8::{Input, Lpd8Message},
+    lpd8::{ConnectionState, FeedbackMessage, Input, Lpd8Message, PadState},
+    script::{Script, ScriptCommand},
 };
 
 pub struct Obs {
@@ -54,6 +60,9 @@ impl Obs {
         self,
         mappings: Mappings,
         mut lpd8_messages: Receiver<Lpd8Message>,
+        feedback: Sender<FeedbackMessage>,
+        scripts: HashMap<String, Script>,
+        min_interval: Duration,
     ) -> Result<JoinHandle<()>> {
         let initial_scene = self.client.scenes().current_program_scene().await?;
 
@@ -62,6 +71,7 @@ impl Obs {
 
         let pc_mappings = mappings.program_changes;
         let cc_mappings = build_cc_mappings(mappings.control_changes);
+        let feedback_mappings = build_feedback_mappings(&pc_mappings);
 
         let events = self.client.events()?;
         let event_handler = spawn(async move {
@@ -71,6 +81,11 @@ impl Obs {
                 inputs: initial_inputs,
             };
 
+            let mut last_sent: HashMap<Input, Instant> = HashMap::new();
+            let mut pending: HashMap<Input, PendingCC> = HashMap::new();
+            let flush = sleep(Duration::from_secs(3600));
+            pin!(flush);
+
             loop {
                 select! {
                     Some(msg) = lpd8_messages.recv() => {
@@ -78,33 +93,111 @@ impl Obs {
                             Lpd8Message::ProgramChange(input) => {
                                 if let Some(action) = pc_mappings.get(&input)
                                     && let Err(e) =
-                                        self.execute_action(action, 0, &current_scene).await
+                                        self.execute_action(action, 0, &current_scene, &scripts).await
                                 {
                                     error!("Unable to execute action {action}: {e}");
                                 }
                             }
                             Lpd8Message::ControlChange(input, value) => {
-                                if let Some(action_with_default) = cc_mappings.get(&input)
-                                    && let Some(action) = action_with_default.get(value)
-                                    && let Err(e) = self.execute_action(action, value, &current_scene).await
+                                let now = Instant::now();
+                                let ready = last_sent
+                                    .get(&input)
+                                    .is_none_or(|last| now.duration_since(*last) >= min_interval);
+
+                                if ready {
+                                    pending.remove(&input);
+                                    last_sent.insert(input, now);
+                                    dispatch_cc(&self, &cc_mappings, &scripts, &current_scene, input, value).await;
+                                } else {
+                                    let due = *last_sent.get(&input).expect("checked above") + min_interval;
+                                    pending.insert(input, PendingCC { value, due });
+                                    reset_flush(flush.as_mut(), &pending);
+                                }
+                            }
+                            Lpd8Message::ConnectionState(ConnectionState::Connected) => {
+                                info!("LPD8 reconnected, resyncing LED feedback");
+                                if feedback.send(FeedbackMessage::Reconnect).await.is_err() {
+                                    error!("Feedback task is gone, cannot resync LED feedback");
+                                } else if let Err(err) = self
+                                    .resync_feedback(&feedback, &feedback_mappings, &current_scene)
+                                    .await
                                 {
-                                    error!("Unable to execute action {action}: {e}");
+                                    error!("Unable to resync LED feedback: {err}");
                                 }
+                            }
+                            Lpd8Message::ConnectionState(ConnectionState::Disconnected) => {
+                                info!("LPD8 disconnected");
+                            }
                         }
-                    }
+                    },
+                    () = &mut flush, if !pending.is_empty() => {
+                        let now = Instant::now();
+                        let due: Vec<Input> = pending
+                            .iter()
+                            .filter(|(_, p)| p.due <= now)
+                            .map(|(input, _)| *input)
+                            .collect();
+
+                        for input in due {
+                            if let Some(p) = pending.remove(&input) {
+                                last_sent.insert(input, now);
+                                dispatch_cc(&self, &cc_mappings, &scripts, &current_scene, input, p.value).await;
+                            }
+                        }
+                        reset_flush(flush.as_mut(), &pending);
                     },
                     Some(event) = events.next() => {
-                        if let Event::CurrentProgramSceneChanged { id } = event {
-                            match gather_scene_inputs(&self.client, id.clone()).await {
-                                Ok(scene_inputs) => {
-                                    current_scene.id = id;
-                                    current_scene.inputs = scene_inputs;
+                        match event {
+                            Event::CurrentProgramSceneChanged { id } => {
+                                match gather_scene_inputs(&self.client, id.clone()).await {
+                                    Ok(scene_inputs) => {
+                                        current_scene.id = id;
+                                        current_scene.inputs = scene_inputs;
+                                    }
+                                    Err(err) => error!(
+                                        "Error while gathering inputs for scene {}: {}",
+                                        id.name, err
+                                    ),
+                                }
+
+                                for (name, pad) in &feedback_mappings.scenes {
+                                    let state = if *name == current_scene.id.name {
+                                        PadState::Active
+                                    } else {
+                                        PadState::Inactive
+                                    };
+                                    send_feedback(&feedback, *pad, state).await;
+                                }
+                            }
+                            Event::InputMuteStateChanged { id, muted } => {
+                                if let Some(name) = self.input_name(&id)
+                                    && let Some(pad) = feedback_mappings.inputs.get(name)
+                                {
+                                    let state = if muted {
+                                        PadState::Muted
+                                    } else {
+                                        PadState::Active
+                                    };
+                                    send_feedback(&feedback, *pad, state).await;
+                                }
+                            }
+                            Event::SceneItemEnableStateChanged { item_id, enabled, .. } => {
+                                if let Some(name) = current_scene
+                                    .inputs
+                                    .iter()
+                                    .find(|(_, id)| **id == item_id)
+                                    .map(|(name, _)| name)
+                                    && let Some(pad) = feedback_mappings.scene_items.get(name)
+                                {
+                                    let state = if enabled {
+                                        PadState::Active
+                                    } else {
+                                        PadState::Inactive
+                                    };
+                                    send_feedback(&feedback, *pad, state).await;
                                 }
-                                Err(err) => error!(
-                                    "Error while gathering inputs for scene {}: {}",
-                                    id.name, err
-                                ),
                             }
+                            _ => {}
                         }
                     },
                 }
@@ -119,6 +212,7 @@ impl Obs {
         action: &Action,
         data: u8,
         current_scene: &CurrentScene,
+        scripts: &HashMap<String, Script>,
     ) -> Result<()> {
         match action {
             Action::SetScene { name } => {
@@ -172,9 +266,197 @@ impl Obs {
                         .await?
                 }
             }
+            Action::ToggleSceneItem { name } => {
+                if let Some(input_id) = current_scene.inputs.get(name) {
+                    let items = self
+                        .client
+                        .scene_items()
+                        .list(current_scene.id.clone().into())
+                        .await?;
+                    if let Some(item) = items.into_iter().find(|i| i.id == *input_id) {
+                        self.client
+                            .scene_items()
+                            .set_enabled(SetEnabled {
+                                scene: current_scene.id.clone().into(),
+                                item_id: *input_id,
+                                enabled: !item.enabled,
+                            })
+                            .await?
+                    }
+                }
+            }
+            Action::Script { path } => {
+                if let Some(script) = scripts.get(path) {
+                    for command in script.run(data, &current_scene.id.name)? {
+                        self.apply_script_command(command, current_scene).await?;
+                    }
+                } else {
+                    error!("Script {path} was not preloaded at startup");
+                }
+            }
         }
         Ok(())
     }
+
+    async fn apply_script_command(
+        &self,
+        command: ScriptCommand,
+        current_scene: &CurrentScene,
+    ) -> Result<()> {
+        match command {
+            ScriptCommand::SetScene(name) => {
+                if let Some(scene_id) = self.scenes.get(&name) {
+                    self.client
+                        .scenes()
+                        .set_current_program_scene(scene_id)
+                        .await?
+                }
+            }
+            ScriptCommand::SetVolume(name, mul) => {
+                if let Some(input_id) = self.inputs.get(&name) {
+                    self.client
+                        .inputs()
+                        .set_volume(input_id.into(), Volume::Mul(mul))
+                        .await?
+                }
+            }
+            ScriptCommand::ToggleMute(name) => {
+                if let Some(input_id) = self.inputs.get(&name) {
+                    self.client.inputs().toggle_mute(input_id.into()).await?;
+                }
+            }
+            ScriptCommand::SetItemEnabled(name, enabled) => {
+                if let Some(input_id) = current_scene.inputs.get(&name) {
+                    self.client
+                        .scene_items()
+                        .set_enabled(SetEnabled {
+                            scene: current_scene.id.clone().into(),
+                            item_id: *input_id,
+                            enabled,
+                        })
+                        .await?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn resync_feedback(
+        &self,
+        feedback: &Sender<FeedbackMessage>,
+        feedback_mappings: &FeedbackMappings,
+        current_scene: &CurrentScene,
+    ) -> Result<()> {
+        for (name, pad) in &feedback_mappings.scenes {
+            let state = if *name == current_scene.id.name {
+                PadState::Active
+            } else {
+                PadState::Inactive
+            };
+            send_feedback(feedback, *pad, state).await;
+        }
+
+        for (name, pad) in &feedback_mappings.inputs {
+            if let Some(input_id) = self.inputs.get(name) {
+                match self.client.inputs().muted(input_id.into()).await {
+                    Ok(muted) => {
+                        let state = if muted {
+                            PadState::Muted
+                        } else {
+                            PadState::Active
+                        };
+                        send_feedback(feedback, *pad, state).await;
+                    }
+                    Err(err) => error!("Unable to read mute state of {name}: {err}"),
+                }
+            }
+        }
+
+        let scene_items = gather_scene_item_states(&self.client, current_scene.id.clone()).await?;
+        for (name, pad) in &feedback_mappings.scene_items {
+            if let Some(enabled) = scene_items.get(name) {
+                let state = if *enabled {
+                    PadState::Active
+                } else {
+                    PadState::Inactive
+                };
+                send_feedback(feedback, *pad, state).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn input_name(&self, id: &InputId) -> Option<&String> {
+        self.inputs
+            .iter()
+            .find(|(_, input_id)| *input_id == id)
+            .map(|(name, _)| name)
+    }
+}
+
+struct PendingCC {
+    value: u8,
+    due: Instant,
+}
+
+fn reset_flush(flush: std::pin::Pin<&mut tokio::time::Sleep>, pending: &HashMap<Input, PendingCC>) {
+    let deadline = pending
+        .values()
+        .map(|p| p.due)
+        .min()
+        .unwrap_or_else(|| Instant::now() + Duration::from_secs(3600));
+    flush.reset(deadline);
+}
+
+async fn dispatch_cc(
+    obs: &Obs,
+    cc_mappings: &HashMap<Input, MappingWithDefault>,
+    scripts: &HashMap<String, Script>,
+    current_scene: &CurrentScene,
+    input: Input,
+    value: u8,
+) {
+    if let Some(action_with_default) = cc_mappings.get(&input)
+        && let Some(action) = action_with_default.get(value)
+        && let Err(e) = obs.execute_action(action, value, current_scene, scripts).await
+    {
+        error!("Unable to execute action {action}: {e}");
+    }
+}
+
+async fn send_feedback(feedback: &Sender<FeedbackMessage>, pad: Input, state: PadState) {
+    if let Err(err) = feedback.send(FeedbackMessage::SetPadState(pad, state)).await {
+        error!("Unable to send feedback for pad {pad:?}: {err}");
+    }
+}
+
+#[derive(Debug, Default)]
+struct FeedbackMappings {
+    scenes: HashMap<String, Input>,
+    inputs: HashMap<String, Input>,
+    scene_items: HashMap<String, Input>,
+}
+
+fn build_feedback_mappings(pc_mappings: &HashMap<Input, Action>) -> FeedbackMappings {
+    let mut mappings = FeedbackMappings::default();
+    for (pad, action) in pc_mappings {
+        match action {
+            Action::SetScene { name } => {
+                mappings.scenes.insert(name.clone(), *pad);
+            }
+            Action::ToggleInput { name } => {
+                mappings.inputs.insert(name.clone(), *pad);
+            }
+            Action::EnableSceneItem { name }
+            | Action::DisableSceneItem { name }
+            | Action::ToggleSceneItem { name } => {
+                mappings.scene_items.insert(name.clone(), *pad);
+            }
+            Action::SetVolume { .. } | Action::Script { .. } => {}
+        }
+    }
+    mappings
 }
 
 #[derive(Debug, Default)]
@@ -220,6 +502,16 @@ async fn gather_scene_inputs(client: &Client, id: SceneId) -> Result<HashMap<Str
         .collect())
 }
 
+async fn gather_scene_item_states(client: &Client, id: SceneId) -> Result<HashMap<String, bool>> {
+    Ok(client
+        .scene_items()
+        .list(id.into())
+        .await?
+        .into_iter()
+        .map(|i| (i.source_name, i.enabled))
+        .collect())
+}
+
 #[derive(Debug)]
 struct CurrentScene {
     id: SceneId,
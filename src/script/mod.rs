@@ -0,0 +1,88 @@
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::Result;
+use mlua::Lua;
+use tokio::{fs::File, io::AsyncReadExt};
+
+#[derive(Debug)]
+pub enum ScriptCommand {
+    SetScene(String),
+    SetVolume(String, f32),
+    ToggleMute(String),
+    SetItemEnabled(String, bool),
+}
+
+pub struct Script {
+    source: String,
+}
+
+impl Script {
+    pub async fn load(path: &str) -> Result<Script> {
+        let mut f = File::open(path).await?;
+        let mut source = String::new();
+        f.read_to_string(&mut source).await?;
+        Ok(Script { source })
+    }
+
+    pub fn run(&self, value: u8, current_scene: &str) -> Result<Vec<ScriptCommand>> {
+        let lua = Lua::new();
+        let commands = Rc::new(RefCell::new(Vec::new()));
+
+        register_api(&lua, &commands)?;
+        lua.globals().set("value", value)?;
+        lua.globals().set("current_scene", current_scene)?;
+
+        lua.load(&self.source).exec()?;
+        drop(lua);
+
+        Ok(commands.borrow_mut().drain(..).collect())
+    }
+}
+
+fn register_api(lua: &Lua, commands: &Rc<RefCell<Vec<ScriptCommand>>>) -> Result<()> {
+    let globals = lua.globals();
+
+    let set_scene = commands.clone();
+    globals.set(
+        "set_scene",
+        lua.create_function(move |_, name: String| {
+            set_scene.borrow_mut().push(ScriptCommand::SetScene(name));
+            Ok(())
+        })?,
+    )?;
+
+    let set_volume = commands.clone();
+    globals.set(
+        "set_volume",
+        lua.create_function(move |_, (name, mul): (String, f32)| {
+            set_volume
+                .borrow_mut()
+                .push(ScriptCommand::SetVolume(name, mul));
+            Ok(())
+        })?,
+    )?;
+
+    let toggle_mute = commands.clone();
+    globals.set(
+        "toggle_mute",
+        lua.create_function(move |_, name: String| {
+            toggle_mute
+                .borrow_mut()
+                .push(ScriptCommand::ToggleMute(name));
+            Ok(())
+        })?,
+    )?;
+
+    let set_item_enabled = commands.clone();
+    globals.set(
+        "set_item_enabled",
+        lua.create_function(move |_, (name, enabled): (String, bool)| {
+            set_item_enabled
+                .borrow_mut()
+                .push(ScriptCommand::SetItemEnabled(name, enabled));
+            Ok(())
+        })?,
+    )?;
+
+    Ok(())
+}